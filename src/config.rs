@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub struct Args {
+    pub directory: PathBuf,
+    pub port: u16,
+    pub allowed_hosts: Vec<String>,
+    pub headers: Vec<(String, String)>,
+    pub disable_preview: bool,
+    pub allow_reload: bool,
+}
+
+pub fn get_app() -> App<'static, 'static> {
+    App::new("mbtileserver")
+        .arg(
+            Arg::with_name("directory")
+                .short("d")
+                .long("directory")
+                .takes_value(true)
+                .default_value(".")
+                .help("Directory to scan for .mbtiles files"),
+        )
+        .arg(
+            Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .takes_value(true)
+                .default_value("8000")
+                .help("Port to serve on"),
+        )
+        .arg(
+            Arg::with_name("allowed_hosts")
+                .long("allowed-hosts")
+                .takes_value(true)
+                .multiple(true)
+                .help("Hosts allowed to connect, defaults to any"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .takes_value(true)
+                .multiple(true)
+                .help("Extra header to add to every response, in 'name:value' form"),
+        )
+        .arg(
+            Arg::with_name("disable_preview")
+                .long("disable-preview")
+                .takes_value(false)
+                .help("Disable the built-in map preview"),
+        )
+        .arg(
+            Arg::with_name("allow_reload")
+                .long("allow-reload")
+                .takes_value(false)
+                .help("Enable the /reload endpoint for re-scanning the tileset directory"),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export a tileset's tile pyramid to a z/x/y directory tree")
+                .arg(
+                    Arg::with_name("tileset")
+                        .required(true)
+                        .help("Path to the .mbtiles file to export"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .required(true)
+                        .help("Directory to write the tile pyramid to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import a z/x/y tile pyramid directory into a new .mbtiles file")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .help("Directory containing the tile pyramid"),
+                )
+                .arg(
+                    Arg::with_name("tileset")
+                        .required(true)
+                        .help("Path to the .mbtiles file to create"),
+                ),
+        )
+}
+
+pub fn parse(matches: ArgMatches) -> Result<Args, String> {
+    let directory = PathBuf::from(matches.value_of("directory").unwrap());
+    if !directory.is_dir() {
+        return Err(format!("{} is not a directory", directory.display()));
+    }
+
+    let port: u16 = matches
+        .value_of("port")
+        .unwrap()
+        .parse()
+        .map_err(|_| "port must be a number between 0 and 65535".to_string())?;
+
+    let allowed_hosts: Vec<String> = matches
+        .values_of("allowed_hosts")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+
+    let headers = matches
+        .values_of("header")
+        .map(|values| {
+            values
+                .filter_map(|header| {
+                    let mut parts = header.splitn(2, ':');
+                    match (parts.next(), parts.next()) {
+                        (Some(name), Some(value)) => {
+                            Some((name.trim().to_string(), value.trim().to_string()))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Args {
+        directory,
+        port,
+        allowed_hosts,
+        headers,
+        disable_preview: matches.is_present("disable_preview"),
+        allow_reload: matches.is_present("allow_reload"),
+    })
+}
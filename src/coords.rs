@@ -0,0 +1,87 @@
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LngLat {
+    pub lng: f64,
+    pub lat: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+/// Flip a tile row between XYZ and TMS ordering (the transform is its own inverse).
+pub fn flip_y(y: u32, z: u32) -> u32 {
+    (1u32 << z) - 1 - y
+}
+
+/// The web-mercator lng/lat bounds covered by an XYZ tile.
+pub fn tile_to_bbox(tile: &Tile) -> BBox {
+    let n = (1u32 << tile.z) as f64;
+    let west = tile.x as f64 / n * 360.0 - 180.0;
+    let east = (tile.x as f64 + 1.0) / n * 360.0 - 180.0;
+    let north = lat_for_row(tile.y as f64, n);
+    let south = lat_for_row(tile.y as f64 + 1.0, n);
+    BBox {
+        west,
+        south,
+        east,
+        north,
+    }
+}
+
+fn lat_for_row(row: f64, n: f64) -> f64 {
+    let lat_rad = (PI * (1.0 - 2.0 * row / n)).sinh().atan();
+    lat_rad.to_degrees()
+}
+
+/// The XYZ tile containing a lng/lat point at the given zoom.
+pub fn lnglat_to_tile(lnglat: &LngLat, z: u32) -> Tile {
+    let n = (1u32 << z) as f64;
+    let x = ((lnglat.lng + 180.0) / 360.0 * n).floor() as u32;
+    let lat_rad = lnglat.lat.to_radians();
+    let y = ((1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / PI) / 2.0 * n).floor() as u32;
+    Tile {
+        x: x.min(n as u32 - 1),
+        y: y.min(n as u32 - 1),
+        z,
+    }
+}
+
+/// Enumerate the tiles covering `bbox` at zoom `z`, clamped to the valid tile range.
+pub fn tiles_in_bbox(bbox: &BBox, z: u32) -> Vec<Tile> {
+    let max_index = (1u32 << z) - 1;
+    let min_tile = lnglat_to_tile(
+        &LngLat {
+            lng: bbox.west,
+            lat: bbox.north,
+        },
+        z,
+    );
+    let max_tile = lnglat_to_tile(
+        &LngLat {
+            lng: bbox.east,
+            lat: bbox.south,
+        },
+        z,
+    );
+
+    let mut tiles = Vec::new();
+    for x in min_tile.x.min(max_index)..=max_tile.x.min(max_index) {
+        for y in min_tile.y.min(max_index)..=max_tile.y.min(max_index) {
+            tiles.push(Tile { x, y, z });
+        }
+    }
+    tiles
+}
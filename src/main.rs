@@ -11,10 +11,15 @@ extern crate serde_json;
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
+use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
 mod config;
+mod coords;
 mod errors;
+mod pyramid;
 mod service;
 mod tiles;
 mod utils;
@@ -23,7 +28,29 @@ mod utils;
 async fn main() {
     pretty_env_logger::init();
 
-    let args = match config::parse(config::get_app().get_matches()) {
+    let matches = config::get_app().get_matches();
+
+    if let Some(sub_matches) = matches.subcommand_matches("export") {
+        let tileset = PathBuf::from(sub_matches.value_of("tileset").unwrap());
+        let output = PathBuf::from(sub_matches.value_of("output").unwrap());
+        if let Err(err) = pyramid::export(&tileset, &output) {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("import") {
+        let input = PathBuf::from(sub_matches.value_of("input").unwrap());
+        let tileset = PathBuf::from(sub_matches.value_of("tileset").unwrap());
+        if let Err(err) = pyramid::import(&input, &tileset) {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let args = match config::parse(matches) {
         Ok(args) => args,
         Err(err) => {
             println!("{}", err);
@@ -33,7 +60,11 @@ async fn main() {
 
     println!("Serving tiles from {}", args.directory.display());
 
-    let tilesets = tiles::discover_tilesets(String::new(), args.directory);
+    let directory = args.directory;
+    let tilesets = Arc::new(RwLock::new(tiles::discover_tilesets(
+        String::new(),
+        directory.clone(),
+    )));
 
     let addr = ([0, 0, 0, 0], args.port).into();
 
@@ -41,11 +72,13 @@ async fn main() {
     let headers = args.headers;
 
     let disable_preview = args.disable_preview;
+    let allow_reload = args.allow_reload;
 
     let make_service = make_service_fn(move |_conn| {
         let tilesets = tilesets.clone();
         let allowed_hosts = allowed_hosts.clone();
         let headers = headers.clone();
+        let directory = directory.clone();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 service::get_service(
@@ -54,6 +87,8 @@ async fn main() {
                     allowed_hosts.clone(),
                     headers.clone(),
                     disable_preview,
+                    allow_reload,
+                    directory.clone(),
                 )
             }))
         }
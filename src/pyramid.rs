@@ -0,0 +1,202 @@
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OpenFlags, NO_PARAMS};
+use serde_json::{json, Value as JSONValue};
+
+use crate::coords::flip_y;
+use crate::errors::{Error, Result};
+use crate::tiles::get_tile_details;
+use crate::utils::{get_data_format, DataFormat};
+
+/// `get_data_format` sniffs a compression wrapper (GZIP/ZLIB) rather than a content kind for
+/// gzip/zlib-wrapped vector tiles, so map those to the format they actually wrap: PBF.
+fn declared_format(sniffed: DataFormat) -> DataFormat {
+    match sniffed {
+        DataFormat::GZIP | DataFormat::ZLIB => DataFormat::PBF,
+        other => other,
+    }
+}
+
+/// Export a discovered tileset to a `z/x/y.<ext>` directory pyramid, applying the TMS->XYZ
+/// row flip, plus a `metadata.json` holding the `metadata` table.
+pub fn export(tileset_path: &Path, output_dir: &Path) -> Result<()> {
+    let tile_name = tileset_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("tileset");
+    let tile_meta = get_tile_details(&tileset_path.to_path_buf(), tile_name)?;
+
+    let connection = Connection::open_with_flags(tileset_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|_| Error)?;
+
+    fs::create_dir_all(output_dir).map_err(|_| Error)?;
+
+    // `get_tile_details` already sniffed the tileset's format from its first tile (and used the
+    // metadata's declared format to correct gzip/zlib-wrapped PBF); use it for every row rather
+    // than re-sniffing each one.
+    let format = declared_format(tile_meta.tile_format);
+    let ext = if format.format().is_empty() {
+        "bin"
+    } else {
+        format.format()
+    };
+
+    let mut statement = connection
+        .prepare(r#"SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles"#)
+        .map_err(|_| Error)?;
+    let mut rows = statement.query(NO_PARAMS).map_err(|_| Error)?;
+
+    while let Some(row) = rows.next().map_err(|_| Error)? {
+        let z: u32 = row.get(0).map_err(|_| Error)?;
+        let x: u32 = row.get(1).map_err(|_| Error)?;
+        let tile_row: u32 = row.get(2).map_err(|_| Error)?;
+        let data: Vec<u8> = row.get(3).map_err(|_| Error)?;
+        let y = flip_y(tile_row, z);
+
+        let tile_dir = output_dir.join(z.to_string()).join(x.to_string());
+        fs::create_dir_all(&tile_dir).map_err(|_| Error)?;
+        File::create(tile_dir.join(format!("{}.{}", y, ext)))
+            .and_then(|mut file| file.write_all(&data))
+            .map_err(|_| Error)?;
+    }
+
+    // MBTiles stores comma-joined "w,s,e,n" bounds, not a JSON array — keep that shape in
+    // metadata.json so `import` (and `get_tile_details`'s `split(",")` parser) round-trip it.
+    let bounds = tile_meta.bounds.as_ref().map(|bounds| {
+        bounds
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    let metadata = json!({
+        "name": tile_meta.name,
+        "version": tile_meta.version,
+        "scheme": tile_meta.scheme,
+        "format": format.format(),
+        "bounds": bounds,
+        "minzoom": tile_meta.minzoom,
+        "maxzoom": tile_meta.maxzoom,
+        "description": tile_meta.description,
+        "attribution": tile_meta.attribution,
+        "legend": tile_meta.legend,
+        "template": tile_meta.template,
+    });
+    File::create(output_dir.join("metadata.json"))
+        .and_then(|mut file| file.write_all(metadata.to_string().as_bytes()))
+        .map_err(|_| Error)?;
+
+    Ok(())
+}
+
+/// Import a `z/x/y.<ext>` tile pyramid (as written by `export`) into a new `.mbtiles` file,
+/// re-creating the standard `tiles`/`metadata` schema and applying the XYZ->TMS row flip.
+pub fn import(input_dir: &Path, tileset_path: &Path) -> Result<()> {
+    let connection = Connection::open(tileset_path).map_err(|_| Error)?;
+    connection
+        .execute_batch(
+            r#"
+            CREATE TABLE metadata (name TEXT, value TEXT);
+            CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            CREATE UNIQUE INDEX metadata_name ON metadata (name);
+            CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+            "#,
+        )
+        .map_err(|_| Error)?;
+
+    let mut has_format = false;
+
+    let metadata_path = input_dir.join("metadata.json");
+    if metadata_path.is_file() {
+        let contents = fs::read_to_string(&metadata_path).map_err(|_| Error)?;
+        let metadata: JSONValue = serde_json::from_str(&contents).map_err(|_| Error)?;
+        if let Some(object) = metadata.as_object() {
+            for (name, value) in object {
+                if value.is_null() {
+                    continue;
+                }
+                let value = match value {
+                    JSONValue::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                if name == "format" {
+                    has_format = true;
+                }
+                connection
+                    .execute(
+                        r#"INSERT INTO metadata (name, value) VALUES (?1, ?2)"#,
+                        params![name, value],
+                    )
+                    .map_err(|_| Error)?;
+            }
+        }
+    }
+
+    let mut inferred_format: Option<DataFormat> = None;
+
+    for zoom_dir in list_numbered_dirs(input_dir)? {
+        let z = zoom_dir.0;
+        for column_dir in list_numbered_dirs(&zoom_dir.1)? {
+            let x = column_dir.0;
+            for tile_file in fs::read_dir(&column_dir.1).map_err(|_| Error)? {
+                let tile_path = tile_file.map_err(|_| Error)?.path();
+                let y: u32 = match tile_path
+                    .file_stem()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.parse().ok())
+                {
+                    Some(y) => y,
+                    None => continue,
+                };
+                let data = fs::read(&tile_path).map_err(|_| Error)?;
+                if inferred_format.is_none() {
+                    inferred_format = Some(declared_format(get_data_format(&data)));
+                }
+                let tile_row = flip_y(y, z);
+                connection
+                    .execute(
+                        r#"INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)"#,
+                        params![z, x, tile_row, data],
+                    )
+                    .map_err(|_| Error)?;
+            }
+        }
+    }
+
+    // metadata.json may omit "format" (MBTiles requires it); infer it from the first tile's
+    // bytes, the same way `get_data_format` is used when exporting.
+    if !has_format {
+        if let Some(format) = inferred_format.filter(|f| !f.format().is_empty()) {
+            connection
+                .execute(
+                    r#"INSERT INTO metadata (name, value) VALUES (?1, ?2)"#,
+                    params!["format", format.format()],
+                )
+                .map_err(|_| Error)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn list_numbered_dirs(dir: &Path) -> Result<Vec<(u32, std::path::PathBuf)>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|_| Error)? {
+        let path = entry.map_err(|_| Error)?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if let Some(n) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse().ok())
+        {
+            dirs.push((n, path));
+        }
+    }
+    Ok(dirs)
+}
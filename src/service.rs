@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use lazy_static::lazy_static;
+use regex::Regex;
+use tokio::sync::RwLock;
+
+use crate::coords::{tiles_in_bbox, BBox};
+use crate::tiles::{self, TileMeta};
+
+pub type TilesetMap = HashMap<String, TileMeta>;
+
+lazy_static! {
+    static ref TILE_RE: Regex =
+        Regex::new(r"^/services/(?P<id>[^/]+)/(?P<z>\d+)/(?P<x>\d+)/(?P<y>\d+)\.(?P<ext>\w+)$")
+            .unwrap();
+    static ref BBOX_RE: Regex = Regex::new(r"^/services/(?P<id>[^/]+)/bbox_tiles$").unwrap();
+    static ref AGG_HASH_RE: Regex = Regex::new(r"^/services/(?P<id>[^/]+)/agg_hash$").unwrap();
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("not found"))
+        .unwrap()
+}
+
+fn forbidden() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::from("forbidden"))
+        .unwrap()
+}
+
+fn is_allowed(req: &Request<Body>, allowed_hosts: &[String]) -> bool {
+    if allowed_hosts.is_empty() {
+        return true;
+    }
+    match req.headers().get("host").and_then(|h| h.to_str().ok()) {
+        Some(host) => allowed_hosts.iter().any(|allowed| allowed == host),
+        None => false,
+    }
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key == name => Some(value.to_string()),
+            _ => None,
+        }
+    })
+}
+
+fn negotiated_format(req: &Request<Body>) -> Option<crate::utils::DataFormat> {
+    let accept = req.headers().get("accept")?.to_str().ok()?;
+    if accept.contains("image/webp") {
+        Some(crate::utils::DataFormat::WEBP)
+    } else {
+        None
+    }
+}
+
+fn accepts_gzip(req: &Request<Body>) -> bool {
+    req.headers()
+        .get("accept-encoding")
+        .and_then(|h| h.to_str().ok())
+        .map(|value| value.contains("gzip"))
+        .unwrap_or(false)
+}
+
+/// Make a stored PBF blob match what the client can accept: pass gzip through untouched,
+/// inflate when the client can't take gzip, or compress raw/zlib bytes when it can.
+fn negotiate_pbf_encoding(data: Vec<u8>, client_accepts_gzip: bool) -> (Vec<u8>, Option<&'static str>) {
+    use crate::utils::DataFormat;
+
+    match (crate::utils::get_data_format(&data), client_accepts_gzip) {
+        (DataFormat::GZIP, true) => (data, Some("gzip")),
+        (DataFormat::GZIP, false) => match crate::utils::decode_bytes(data.clone(), DataFormat::GZIP) {
+            Ok(raw) => (raw, None),
+            Err(_) => (data, None),
+        },
+        (DataFormat::ZLIB, true) => {
+            match crate::utils::decode_bytes(data.clone(), DataFormat::ZLIB) {
+                Ok(raw) => (crate::utils::encode(&raw), Some("gzip")),
+                Err(_) => (data, None),
+            }
+        }
+        (DataFormat::ZLIB, false) => {
+            match crate::utils::decode_bytes(data.clone(), DataFormat::ZLIB) {
+                Ok(raw) => (raw, None),
+                Err(_) => (data, None),
+            }
+        }
+        (_, true) => (crate::utils::encode(&data), Some("gzip")),
+        (_, false) => (data, None),
+    }
+}
+
+fn apply_headers(mut response: Response<Body>, headers: &[(String, String)]) -> Response<Body> {
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(name.as_bytes()),
+            hyper::header::HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+fn bbox_tiles(tile_meta: &TileMeta, query: &str) -> Response<Body> {
+    let bbox_param = match query_param(query, "bbox") {
+        Some(value) => value,
+        None => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("missing bbox query parameter"))
+                .unwrap()
+        }
+    };
+    let zoom: u32 = match query_param(query, "zoom").and_then(|z| z.parse().ok()) {
+        Some(zoom) => zoom,
+        None => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("missing zoom query parameter"))
+                .unwrap()
+        }
+    };
+
+    let coords: Vec<f64> = bbox_param
+        .split(',')
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    if coords.len() != 4 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("bbox must be 'west,south,east,north'"))
+            .unwrap();
+    }
+    let bbox = BBox {
+        west: coords[0],
+        south: coords[1],
+        east: coords[2],
+        north: coords[3],
+    };
+
+    let ext = tile_meta.tile_format.format();
+    let urls: Vec<String> = tiles_in_bbox(&bbox, zoom)
+        .into_iter()
+        .map(|tile| {
+            format!(
+                "/services/{}/{}/{}/{}.{}",
+                tile_meta.id, tile.z, tile.x, tile.y, ext
+            )
+        })
+        .collect();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::json!({ "tiles": urls }).to_string()))
+        .unwrap()
+}
+
+async fn reload(tilesets: &Arc<RwLock<TilesetMap>>, directory: &PathBuf) -> Response<Body> {
+    let fresh = tiles::discover_tilesets(String::new(), directory.clone());
+
+    let mut current = tilesets.write().await;
+    let added: Vec<&String> = fresh.keys().filter(|id| !current.contains_key(*id)).collect();
+    let removed: Vec<&String> = current.keys().filter(|id| !fresh.contains_key(*id)).collect();
+
+    let summary = serde_json::json!({
+        "added": added,
+        "removed": removed,
+    });
+
+    *current = fresh;
+    drop(current);
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(summary.to_string()))
+        .unwrap()
+}
+
+pub async fn get_service(
+    req: Request<Body>,
+    tilesets: Arc<RwLock<TilesetMap>>,
+    allowed_hosts: Vec<String>,
+    headers: Vec<(String, String)>,
+    disable_preview: bool,
+    allow_reload: bool,
+    directory: PathBuf,
+) -> Result<Response<Body>, hyper::Error> {
+    let _ = disable_preview;
+
+    if !is_allowed(&req, &allowed_hosts) {
+        return Ok(forbidden());
+    }
+
+    let response = if req.method() == Method::GET && req.uri().path() == "/reload" {
+        if allow_reload {
+            reload(&tilesets, &directory).await
+        } else {
+            forbidden()
+        }
+    } else if let Some(captures) = TILE_RE.captures(req.uri().path()) {
+        let id = &captures["id"];
+        let z: u32 = captures["z"].parse().unwrap_or(0);
+        let x: u32 = captures["x"].parse().unwrap_or(0);
+        let y: u32 = captures["y"].parse().unwrap_or(0);
+
+        let query = req.uri().query().unwrap_or("");
+        let target_format = query_param(query, "format")
+            .map(|f| crate::utils::DataFormat::new(&f))
+            .or_else(|| negotiated_format(&req));
+        let optimize = query_param(query, "optimize").as_deref() == Some("1");
+
+        let if_none_match = req
+            .headers()
+            .get("if-none-match")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.trim_matches('"').to_string());
+
+        let tilesets = tilesets.read().await;
+        match tilesets.get(id) {
+            Some(tile_meta) => {
+                match tiles::get_tile_data_for_format(
+                    tile_meta,
+                    z,
+                    x,
+                    y,
+                    target_format,
+                    optimize,
+                ) {
+                    Ok((data, data_format)) => {
+                        let etag = crate::utils::hash_bytes(&data);
+                        if if_none_match.as_deref() == Some(etag.as_str()) {
+                            Response::builder()
+                                .status(StatusCode::NOT_MODIFIED)
+                                .header("etag", format!("\"{}\"", etag))
+                                .body(Body::empty())
+                                .unwrap()
+                        } else {
+                            let mut builder = Response::builder()
+                                .status(StatusCode::OK)
+                                .header("content-type", data_format.content_type())
+                                .header("etag", format!("\"{}\"", etag));
+
+                            let body = if data_format == crate::utils::DataFormat::PBF {
+                                let (body, content_encoding) =
+                                    negotiate_pbf_encoding(data, accepts_gzip(&req));
+                                if let Some(encoding) = content_encoding {
+                                    builder = builder.header("content-encoding", encoding);
+                                }
+                                body
+                            } else {
+                                data
+                            };
+
+                            builder.body(Body::from(body)).unwrap()
+                        }
+                    }
+                    Err(_) => not_found(),
+                }
+            }
+            None => not_found(),
+        }
+    } else if let Some(captures) = AGG_HASH_RE.captures(req.uri().path()) {
+        let id = &captures["id"];
+        let tilesets = tilesets.read().await;
+        match tilesets.get(id) {
+            Some(tile_meta) => match tiles::get_agg_hash(&tile_meta.path) {
+                Ok(hash) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "agg_hash": hash }).to_string(),
+                    ))
+                    .unwrap(),
+                Err(_) => not_found(),
+            },
+            None => not_found(),
+        }
+    } else if let Some(captures) = BBOX_RE.captures(req.uri().path()) {
+        let id = captures["id"].to_string();
+        let query = req.uri().query().unwrap_or("").to_string();
+        let tilesets = tilesets.read().await;
+        match tilesets.get(&id) {
+            Some(tile_meta) => bbox_tiles(tile_meta, &query),
+            None => not_found(),
+        }
+    } else {
+        not_found()
+    };
+
+    Ok(apply_headers(response, &headers))
+}
@@ -2,7 +2,10 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::read_dir;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
+use lazy_static::lazy_static;
+use lru::LruCache;
 use rusqlite::{params, Connection, OpenFlags, NO_PARAMS};
 
 use serde;
@@ -11,7 +14,15 @@ use serde_json::Value as JSONValue;
 
 use crate::errors::{Error, Result};
 
-use crate::utils::{decode, get_data_format, DataFormat};
+use crate::coords::flip_y;
+use crate::utils::{decode, get_data_format, optimize_png, transcode_to_webp, DataFormat};
+
+type TranscodeKey = (String, u32, u32, u32, DataFormat, bool);
+
+lazy_static! {
+    static ref TRANSCODE_CACHE: Mutex<LruCache<TranscodeKey, (Vec<u8>, DataFormat)>> =
+        Mutex::new(LruCache::new(256));
+}
 
 #[derive(Clone, Debug)]
 pub struct TileMeta {
@@ -168,6 +179,15 @@ pub fn get_tile_details<'a>(path: &PathBuf, tile_name: &str) -> Result<TileMeta>
             "attribution" => metadata.attribution = Some(value),
             "legend" => metadata.legend = Some(value),
             "template" => metadata.template = Some(value),
+            "scheme" => metadata.scheme = value,
+            // a gzip/zlib-wrapped PBF tile sniffs as GZIP/ZLIB by its magic bytes alone, so
+            // fall back to the metadata's declared format to tell PBF apart from real gzip.
+            "format" => match (metadata.tile_format, DataFormat::new(&value)) {
+                (DataFormat::GZIP, DataFormat::PBF) | (DataFormat::ZLIB, DataFormat::PBF) => {
+                    metadata.tile_format = DataFormat::PBF
+                }
+                _ => (),
+            },
             _ => (),
         }
     }
@@ -213,7 +233,10 @@ fn get_grid_info(connection: &Connection) -> Option<DataFormat> {
     None
 }
 
-pub fn get_grid_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Result<UTFGrid> {
+pub fn get_grid_data(tile_path: &PathBuf, z: u32, x: u32, y: u32, scheme: &str) -> Result<UTFGrid> {
+    // MBTiles stores rows in TMS order; a standard XYZ request must be flipped unless the
+    // tileset explicitly declares itself already in TMS row order.
+    let tile_row = if scheme != "tms" { flip_y(y, z) } else { y };
     let connection =
         Connection::open_with_flags(tile_path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
     let mut statement = connection
@@ -226,7 +249,7 @@ pub fn get_grid_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Result<UTFG
             "#,
         )
         .unwrap();
-    let grid_data = match statement.query_row(params![z, x, y], |row| {
+    let grid_data = match statement.query_row(params![z, x, tile_row], |row| {
         Ok(row.get::<_, Vec<u8>>(0).unwrap())
     }) {
         Ok(d) => d,
@@ -252,7 +275,7 @@ pub fn get_grid_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Result<UTFG
         )
         .unwrap(); // TODO handle error
     let grid_data_iter = statement
-        .query_map(params![z, x, y], |row| {
+        .query_map(params![z, x, tile_row], |row| {
             Ok((
                 row.get::<_, String>(0).unwrap(),
                 row.get::<_, String>(1).unwrap(),
@@ -268,7 +291,10 @@ pub fn get_grid_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Result<UTFG
     Ok(grid_data)
 }
 
-pub fn get_tile_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Vec<u8> {
+pub fn get_tile_data(tile_path: &PathBuf, z: u32, x: u32, y: u32, scheme: &str) -> Vec<u8> {
+    // MBTiles stores rows in TMS order; a standard XYZ request must be flipped unless the
+    // tileset explicitly declares itself already in TMS row order.
+    let tile_row = if scheme != "tms" { flip_y(y, z) } else { y };
     let connection =
         Connection::open_with_flags(tile_path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
 
@@ -283,10 +309,87 @@ pub fn get_tile_data(tile_path: &PathBuf, z: u32, x: u32, y: u32) -> Vec<u8> {
         )
         .unwrap(); // TODO handle error
     statement
-        .query_row(params![z, x, y], |row| Ok(row.get(0).unwrap()))
+        .query_row(params![z, x, tile_row], |row| Ok(row.get(0).unwrap()))
         .unwrap_or(get_blank_image())
 }
 
+/// Fetch a raster tile's bytes, optionally transcoding to `target_format` (e.g. WebP) and/or
+/// running a lossless PNG optimization pass, caching the result so repeat requests are cheap.
+pub fn get_tile_data_for_format(
+    tile_meta: &TileMeta,
+    z: u32,
+    x: u32,
+    y: u32,
+    target_format: Option<DataFormat>,
+    optimize: bool,
+) -> Result<(Vec<u8>, DataFormat)> {
+    let tile_format = tile_meta.tile_format;
+    let cache_key: TranscodeKey = (
+        tile_meta.id.clone(),
+        z,
+        x,
+        y,
+        target_format.unwrap_or(tile_format),
+        optimize,
+    );
+
+    if target_format.is_some() || optimize {
+        if let Some((cached, cached_format)) = TRANSCODE_CACHE.lock().unwrap().get(&cache_key) {
+            return Ok((cached.clone(), *cached_format));
+        }
+    }
+
+    let data = get_tile_data(&tile_meta.path, z, x, y, &tile_meta.scheme);
+
+    let (data, data_format, transcoded) = match (tile_format, target_format, optimize) {
+        (DataFormat::PNG, Some(DataFormat::WEBP), _) | (DataFormat::JPG, Some(DataFormat::WEBP), _) => {
+            (transcode_to_webp(&data)?, DataFormat::WEBP, true)
+        }
+        (DataFormat::PNG, _, true) => (optimize_png(&data)?, DataFormat::PNG, true),
+        _ => (data, tile_format, false),
+    };
+
+    // Only cache bytes that were actually produced by a transcode/optimize pass: a pass-through
+    // response (e.g. `?format=webp` on a non-raster tileset) must keep reporting the real format
+    // on every request, not whatever format happened to be in the cache key.
+    if transcoded {
+        TRANSCODE_CACHE
+            .lock()
+            .unwrap()
+            .put(cache_key, (data.clone(), data_format));
+    }
+
+    Ok((data, data_format))
+}
+
+/// Fold a content hash over every row of the `tiles` table, ordered by z/x/y, so clients and
+/// CDNs can cheaply detect whether an entire tileset changed since a prior sync.
+pub fn get_agg_hash(tile_path: &PathBuf) -> Result<String> {
+    let connection = match Connection::open_with_flags(tile_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    {
+        Ok(connection) => connection,
+        Err(_) => return Err(Error),
+    };
+
+    let mut statement = connection
+        .prepare(
+            r#"SELECT tile_data
+                 FROM tiles
+                ORDER BY zoom_level, tile_column, tile_row
+            "#,
+        )
+        .map_err(|_| Error)?;
+    let mut rows = statement.query(NO_PARAMS).map_err(|_| Error)?;
+
+    let mut hasher = blake3::Hasher::new();
+    while let Some(row) = rows.next().map_err(|_| Error)? {
+        let data: Vec<u8> = row.get(0).map_err(|_| Error)?;
+        hasher.update(&data);
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
 pub fn get_blank_image() -> Vec<u8> {
     let image = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x01\x00\x00\x00\x01\x00\x01\x03\x00\x00\x00f\xbc:%\x00\x00\x00\x03PLTE\x00\x00\x00\xa7z=\xda\x00\x00\x00\x01tRNS\x00@\xe6\xd8f\x00\x00\x00\x1fIDATh\xde\xed\xc1\x01\r\x00\x00\x00\xc2 \xfb\xa76\xc77`\x00\x00\x00\x00\x00\x00\x00\x00q\x07!\x00\x00\x01\xa7W)\xd7\x00\x00\x00\x00IEND\xaeB`\x82";
     image.to_vec()
@@ -3,13 +3,15 @@ use std::io::prelude::*;
 use flate2::read::{GzDecoder, ZlibDecoder};
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{GenericImageView, ImageEncoder, ImageOutputFormat};
 
 use serde;
 use serde::{Deserialize, Serialize};
 
 use crate::errors::{Error, Result};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum DataFormat {
     PNG,
@@ -63,37 +65,80 @@ impl DataFormat {
     }
 }
 
-pub fn decode(data: Vec<u8>, data_type: DataFormat) -> Result<String> {
+/// Inflate a GZIP/ZLIB-wrapped blob to its raw bytes.
+pub fn decode_bytes(data: Vec<u8>, data_type: DataFormat) -> Result<Vec<u8>> {
     match data_type {
         DataFormat::GZIP => {
             let mut z = GzDecoder::new(&data[..]);
-            let mut s = String::new();
-            z.read_to_string(&mut s).unwrap();
-            Ok(s)
+            let mut buf = Vec::new();
+            z.read_to_end(&mut buf).map_err(|_| Error)?;
+            Ok(buf)
         }
         DataFormat::ZLIB => {
             let mut z = ZlibDecoder::new(&data[..]);
-            let mut s = String::new();
-            z.read_to_string(&mut s).unwrap();
-            Ok(s)
+            let mut buf = Vec::new();
+            z.read_to_end(&mut buf).map_err(|_| Error)?;
+            Ok(buf)
         }
         _ => Err(Error),
     }
 }
 
+pub fn decode(data: Vec<u8>, data_type: DataFormat) -> Result<String> {
+    let bytes = decode_bytes(data, data_type)?;
+    String::from_utf8(bytes).map_err(|_| Error)
+}
+
 pub fn encode(data: &[u8]) -> Vec<u8> {
     let mut e = GzEncoder::new(Vec::new(), Compression::default());
     e.write_all(data).unwrap();
     e.finish().unwrap()
 }
 
+/// Hex-encoded blake3 digest of `data`, used as a strong ETag / content hash.
+pub fn hash_bytes(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+/// Decode a PNG/JPG blob and re-encode it as WebP, for on-the-fly raster transcoding.
+pub fn transcode_to_webp(data: &[u8]) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(data).map_err(|_| Error)?;
+    let mut out = Vec::new();
+    image
+        .write_to(&mut out, ImageOutputFormat::WebP)
+        .map_err(|_| Error)?;
+    Ok(out)
+}
+
+/// Re-encode a PNG at the best zlib compression level with adaptive per-row filtering,
+/// dropping ancillary chunks the decoder doesn't round-trip (an oxipng-style pass without
+/// pulling in oxipng itself).
+pub fn optimize_png(data: &[u8]) -> Result<Vec<u8>> {
+    let image =
+        image::load_from_memory_with_format(data, image::ImageFormat::Png).map_err(|_| Error)?;
+    let (width, height) = image.dimensions();
+    let color = image.color();
+
+    let mut out = Vec::new();
+    PngEncoder::new_with_quality(&mut out, CompressionType::Best, FilterType::Adaptive)
+        .write_image(image.as_bytes(), width, height, color)
+        .map_err(|_| Error)?;
+    Ok(out)
+}
+
 pub fn get_data_format(data: &Vec<u8>) -> DataFormat {
+    // Empty/near-empty tiles are legal in MBTiles, so guard each magic-byte slice by length
+    // before indexing instead of panicking on short blobs.
     match data {
-        v if &v[0..2] == b"\x1f\x8b" => DataFormat::GZIP, // this masks PBF format too
-        v if &v[0..2] == b"\x78\x9c" => DataFormat::ZLIB,
-        v if &v[0..8] == b"\x89\x50\x4E\x47\x0D\x0A\x1A\x0A" => DataFormat::PNG,
-        v if &v[0..3] == b"\xFF\xD8\xFF" => DataFormat::JPG,
-        v if &v[0..14] == b"\x52\x49\x46\x46\xc0\x00\x00\x00\x57\x45\x42\x50\x56\x50" => {
+        v if matches!(v.get(0..2), Some(b"\x1f\x8b")) => DataFormat::GZIP, // this masks PBF format too
+        v if matches!(v.get(0..2), Some(b"\x78\x9c")) => DataFormat::ZLIB,
+        v if matches!(v.get(0..8), Some(b"\x89\x50\x4E\x47\x0D\x0A\x1A\x0A")) => DataFormat::PNG,
+        v if matches!(v.get(0..3), Some(b"\xFF\xD8\xFF")) => DataFormat::JPG,
+        v if matches!(
+            v.get(0..14),
+            Some(b"\x52\x49\x46\x46\xc0\x00\x00\x00\x57\x45\x42\x50\x56\x50")
+        ) =>
+        {
             DataFormat::WEBP
         }
         _ => DataFormat::UNKNOWN,